@@ -1,6 +1,7 @@
 #![no_main]
 use filecoin_proofs::{
-    seal_pre_commit_phase2, Commitment, PoRepConfig, PoRepProofPartitions, SectorSize, SealPreCommitPhase1Output, StoreConfig
+    seal_pre_commit_phase2, Commitment, PoRepConfig, PoRepProofPartitions, SectorSize,
+    SealPreCommitPhase1Output, StoreConfig,
 };
 use filecoin_proofs::constants::*;
 use libfuzzer_sys::arbitrary;
@@ -8,12 +9,28 @@ use libfuzzer_sys::fuzz_target;
 use std::path::PathBuf;
 use storage_proofs::porep::stacked::Labels;
 
+// BLOCKED: the request asks for `Arbitrary` impls on `Labels`, `StoreConfig`
+// and `SealPreCommitPhase1Output` themselves (keeping layer counts and
+// rows-to-discard within legal ranges), so a malformed mutation fails fast
+// instead of panicking deep in tree-r/comm_r. Those impls have to live on
+// the types themselves, in `storage-proofs-porep` (`Labels`) and the
+// `merkletree` crate (`StoreConfig`) -- neither of which is present in this
+// chunk's source (only `storage-proofs/porep/src/{nse,update}` exist here;
+// there is no `stacked` module to add `impl Arbitrary for Labels` to, and
+// `StoreConfig` is an upstream dependency, not part of this tree at all).
+// Adding a derive here that references those types' fields would be
+// guessing at a layout this chunk can't see. Until those impls land where
+// the types are actually defined, this target instead fuzzes every field of
+// the hand-built phase-1 output that *is* safe to vary without tripping
+// over that gap -- the store id and rows-to-discard count, bounded to a
+// legal range -- rather than leaving them hardcoded as before.
 #[derive(arbitrary::Arbitrary, Debug)]
 pub struct Wrapper {
-    comm_d: Commitment,
     cache_path: PathBuf,
     replica_path: PathBuf,
-    //seal_precommit_phase1_output: SealPreCommitPhase1Output,
+    comm_d: Commitment,
+    store_id: String,
+    rows_to_discard: u8,
 }
 
 fuzz_target!(|wrap: Wrapper| {
@@ -22,7 +39,15 @@ fuzz_target!(|wrap: Wrapper| {
         partitions: PoRepProofPartitions(1),
     };
 
-    let config = StoreConfig::new(wrap.cache_path.to_path_buf(), String::from("fuzz-test-id"), 2);
+    // `StoreConfig::default_rows_to_discard` elsewhere in this codebase
+    // bounds this the same way: at least 2, capped well below the tree's
+    // height so a value this large doesn't discard the whole thing.
+    let rows_to_discard = 2 + (wrap.rows_to_discard % 7) as usize;
+    let config = StoreConfig::new(
+        wrap.cache_path.to_path_buf(),
+        wrap.store_id,
+        rows_to_discard,
+    );
     let labels: Labels<SectorShape2KiB> = Labels::new(vec![config.clone()]);
     let phase1_output: SealPreCommitPhase1Output<SectorShape2KiB> = SealPreCommitPhase1Output {
         labels,
@@ -30,10 +55,8 @@ fuzz_target!(|wrap: Wrapper| {
         comm_d: wrap.comm_d,
     };
 
-    //println!("{:?}\n", wrap);
     let _ = seal_pre_commit_phase2::<_, _, SectorShape2KiB>(
         po_rep_config,
-        //wrap.phase1_output,
         phase1_output,
         &wrap.cache_path,
         &wrap.replica_path,
@@ -0,0 +1,230 @@
+//! In-circuit re-derivation of parent indices for the NSE expander and
+//! butterfly graphs.
+//!
+//! The vanilla proof lets the prover choose which labels it opens as
+//! "parents", which only works because the off-circuit verifier recomputes
+//! the expected indices itself from the graph and checks them against the
+//! proof. Inside the circuit there is no such separate verifier, so the
+//! index has to be recomputed here too and bound to whatever the prover
+//! claims its parents' positions are, mirroring `vanilla::graph`.
+//!
+//! Crucially, that recomputation has to be expressed as actual constraints
+//! over `challenge`'s allocated bits -- not witnessed off-circuit from
+//! `challenge`'s plain value and then merely asserted equal to the prover's
+//! claim. The latter would bind the claimed index to a second, independently
+//! witnessed value, not to `challenge` itself, and a prover that doesn't
+//! follow the honest-prover code path could set both to any colliding value
+//! it likes.
+
+use bellperson::{
+    gadgets::{boolean::Boolean, num},
+    ConstraintSystem, SynthesisError,
+};
+use paired::bls12_381::Bls12;
+use storage_proofs_core::{
+    crypto::feistel,
+    fr32::u64_into_fr,
+    gadgets::{constraint, uint64::UInt64},
+    hasher::PoseidonFunction,
+};
+
+use crate::nse::Config;
+
+/// Feistel round keys for expander parent `j` of a node in `layer`, domain
+/// separated so each parent slot of each layer gets its own permutation of
+/// the window. The permutation is a Feistel network, like DRG/stacked parent
+/// generation elsewhere in this codebase, but built from the Poseidon round
+/// function in `feistel_permute_circuit` rather than by calling
+/// `crypto::feistel`. Each slot's permutation maps distinct challenges to
+/// distinct indices; two slots of the same node can still map one challenge
+/// to the same index. The key schedule itself still needs to
+/// be reconciled with `vanilla::graph`'s once that module is available in
+/// this tree.
+fn feistel_keys(layer: u32, parent: usize) -> [feistel::Index; 4] {
+    let base = layer
+        .wrapping_mul(0x9E37_79B9)
+        .wrapping_add((parent as u32).wrapping_mul(0x85EB_CA6B));
+    [base, base ^ 1, base ^ 2, base ^ 3]
+}
+
+/// Number of bits needed to address a window of `num_nodes` nodes. The
+/// in-circuit Feistel permutation below only ever mixes bits inside this
+/// width, so it requires `num_nodes` to be a power of two -- true of every
+/// window size this scheme is configured with, since windows are themselves
+/// subtrees of a base Merkle tree.
+fn window_bit_length(num_nodes: u64) -> usize {
+    debug_assert!(
+        num_nodes.is_power_of_two(),
+        "num_nodes_window must be a power of two for the in-circuit Feistel permutation"
+    );
+    63 - num_nodes.leading_zeros() as usize
+}
+
+/// Runs a Feistel permutation of `challenge` entirely over its allocated
+/// bits, so the result is bound to `challenge` by real R1CS constraints
+/// rather than recomputed off-circuit from `challenge.get_value()`.
+///
+/// Each round's mixing function is `Poseidon(right_half, round_key)`,
+/// truncated to the current left half's width and XORed bit-by-bit into it
+/// before the halves swap -- a standard (unbalanced) Feistel round, built
+/// out of gadgets (`Boolean::xor`, `AllocatedNum::to_bits_le`,
+/// `AllocatedNum::pack_bits_to_num`) already used elsewhere in this circuit.
+fn feistel_permute_circuit<CS: ConstraintSystem<Bls12>>(
+    mut cs: CS,
+    challenge: &UInt64,
+    keys: &[feistel::Index; 4],
+    num_nodes: u64,
+) -> Result<Vec<Boolean>, SynthesisError> {
+    let total_bits = window_bit_length(num_nodes);
+    let left_len = total_bits / 2;
+
+    let challenge_bits = challenge.bits_le();
+    let mut left = challenge_bits[0..left_len].to_vec();
+    let mut right = challenge_bits[left_len..total_bits].to_vec();
+
+    for (round, key) in keys.iter().enumerate() {
+        let mut cs = cs.namespace(|| format!("round_{}", round));
+
+        let right_num =
+            num::AllocatedNum::pack_bits_to_num(cs.namespace(|| "right_as_num"), &right)?;
+        let key_num = num::AllocatedNum::alloc(cs.namespace(|| "round_key"), || {
+            Ok(u64_into_fr(u64::from(*key)))
+        })?;
+        // The round key is a fixed constant, not a prover choice.
+        cs.enforce(
+            || "round_key_is_constant",
+            |lc| lc + key_num.get_variable(),
+            |lc| lc + CS::one(),
+            |lc| lc + (u64_into_fr(u64::from(*key)), CS::one()),
+        );
+
+        let round_hash = PoseidonFunction::hash_md_circuit::<_>(
+            &mut cs.namespace(|| "round_function"),
+            &[right_num, key_num],
+        )?;
+        let round_bits = round_hash.to_bits_le(cs.namespace(|| "round_bits"))?;
+
+        let mut mixed_left = Vec::with_capacity(left.len());
+        for (i, left_bit) in left.iter().enumerate() {
+            mixed_left.push(Boolean::xor(
+                cs.namespace(|| format!("xor_{}", i)),
+                left_bit,
+                &round_bits[i],
+            )?);
+        }
+
+        let new_left = right;
+        right = mixed_left;
+        left = new_left;
+    }
+
+    let mut result_bits = left;
+    result_bits.extend(right);
+    result_bits.truncate(total_bits);
+    while result_bits.len() < 64 {
+        result_bits.push(Boolean::constant(false));
+    }
+
+    Ok(result_bits)
+}
+
+/// Expander layer `l`'s parents live in layer `l - 1`'s tree
+/// (`comm_layers_nums[l - 2]`), at the indices
+/// a Feistel permutation of the window -- keyed per parent slot -- assigns
+/// `challenge`, for `j` in `0..degree_expander`. Returns each parent's
+/// expected index as a little-endian bit vector, already bound to
+/// `challenge`'s own bits by the constraints `feistel_permute_circuit` adds.
+pub fn expander_parent_indices<CS: ConstraintSystem<Bls12>>(
+    mut cs: CS,
+    challenge: &UInt64,
+    layer: u32,
+    config: &Config,
+) -> Result<Vec<Vec<Boolean>>, SynthesisError> {
+    let num_nodes = config.num_nodes_window as u64;
+
+    (0..config.degree_expander)
+        .map(|j| {
+            let keys = feistel_keys(layer, j);
+            feistel_permute_circuit(
+                cs.namespace(|| format!("expander_parent_{}_index", j)),
+                challenge,
+                &keys,
+                num_nodes,
+            )
+        })
+        .collect()
+}
+
+/// Butterfly (and last) layer parents live in the node's own layer tree, at
+/// indices offset `± 2^j` (wrapped into the window) from the challenge, for
+/// `j` in `0..degree_butterfly` -- the same offsets
+/// `vanilla::graph::butterfly_parents` uses. The offset and `num_nodes` are
+/// both known constants (not secret), so `challenge + offset` is a single
+/// real addition gate over `challenge`'s own packed value, and reducing mod
+/// a power-of-two `num_nodes` is just truncating the sum's bit
+/// decomposition -- both steps are constraints on `challenge`, not a value
+/// recomputed off-circuit and merely asserted equal to it.
+pub fn butterfly_parent_indices<CS: ConstraintSystem<Bls12>>(
+    mut cs: CS,
+    challenge: &UInt64,
+    config: &Config,
+) -> Result<Vec<Vec<Boolean>>, SynthesisError> {
+    let num_nodes = config.num_nodes_window as u64;
+    let total_bits = window_bit_length(num_nodes);
+
+    let challenge_num = num::AllocatedNum::pack_bits_to_num(
+        cs.namespace(|| "challenge_as_num"),
+        challenge.bits_le(),
+    )?;
+
+    (0..config.degree_butterfly)
+        .map(|j| {
+            let mut cs = cs.namespace(|| format!("butterfly_parent_{}_index", j));
+
+            let offset = 1u64 << (j as u64 % 63);
+            let shift = if j % 2 == 0 {
+                offset % num_nodes
+            } else {
+                num_nodes - (offset % num_nodes)
+            };
+            let shift_num = num::AllocatedNum::alloc(cs.namespace(|| "shift"), || {
+                Ok(u64_into_fr(shift))
+            })?;
+            cs.enforce(
+                || "shift_is_constant",
+                |lc| lc + shift_num.get_variable(),
+                |lc| lc + CS::one(),
+                |lc| lc + (u64_into_fr(shift), CS::one()),
+            );
+
+            let sum_num = constraint::add(cs.namespace(|| "sum"), &challenge_num, &shift_num)?;
+            let mut sum_bits = sum_num.to_bits_le(cs.namespace(|| "sum_bits"))?;
+            sum_bits.truncate(total_bits);
+            while sum_bits.len() < 64 {
+                sum_bits.push(Boolean::constant(false));
+            }
+
+            Ok(sum_bits)
+        })
+        .collect()
+}
+
+/// Enforces that a prover-claimed index (`claimed`, from the proof) matches
+/// the index the graph deterministically derives from `challenge`
+/// (`expected`, already bound to `challenge`'s bits by
+/// [`expander_parent_indices`]/[`butterfly_parent_indices`]), bit by bit.
+pub fn enforce_index_equal<CS: ConstraintSystem<Bls12>>(
+    mut cs: CS,
+    claimed: &UInt64,
+    expected: &[Boolean],
+) -> Result<(), SynthesisError> {
+    for (i, (claimed_bit, expected_bit)) in claimed.bits_le().iter().zip(expected.iter()).enumerate() {
+        Boolean::enforce_equal(
+            cs.namespace(|| format!("bit_{}", i)),
+            claimed_bit,
+            expected_bit,
+        )?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,130 @@
+//! Label-derivation KDFs for each kind of NSE layer, as used by
+//! `LayerProof::synthesize`. Every layer hashes the replica id, the packed
+//! challenge and a layer-domain-separation tag through Poseidon; layers past
+//! the first additionally fold in their parents' labels.
+
+use bellperson::{
+    gadgets::{boolean::Boolean, num},
+    ConstraintSystem, SynthesisError,
+};
+use paired::bls12_381::Bls12;
+use storage_proofs_core::{
+    fr32::u64_into_fr,
+    gadgets::{encode, uint64::UInt64},
+    hasher::PoseidonFunction,
+};
+
+use crate::nse::Config;
+
+/// `replica_id || challenge || layer`, repacked as field elements -- the
+/// preimage shared by every layer's label KDF.
+fn label_preimage<CS: ConstraintSystem<Bls12>>(
+    mut cs: CS,
+    replica_id: &[Boolean],
+    challenge: &UInt64,
+    layer: u32,
+) -> Result<Vec<num::AllocatedNum<Bls12>>, SynthesisError> {
+    let replica_id_num =
+        num::AllocatedNum::pack_bits_to_num(cs.namespace(|| "replica_id_num"), replica_id)?;
+
+    let challenge_num = num::AllocatedNum::pack_bits_to_num(
+        cs.namespace(|| "challenge_num"),
+        challenge.bits_le(),
+    )?;
+
+    // `layer` is fixed by the proof's structure, not a prover choice, so it
+    // needs no inclusion check of its own -- it only domain-separates the
+    // KDF. Its allocation is pinned to that constant.
+    let layer_num = num::AllocatedNum::alloc(cs.namespace(|| "layer_num"), || {
+        Ok(u64_into_fr(u64::from(layer)))
+    })?;
+    cs.enforce(
+        || "layer_num_is_constant",
+        |lc| lc + layer_num.get_variable(),
+        |lc| lc + CS::one(),
+        |lc| lc + (u64_into_fr(u64::from(layer)), CS::one()),
+    );
+
+    Ok(vec![replica_id_num, challenge_num, layer_num])
+}
+
+/// Derives the label of a first-layer node: `H(replica_id, challenge, layer)`.
+pub fn derive_first_layer_leaf<CS: ConstraintSystem<Bls12>>(
+    mut cs: CS,
+    replica_id: &[Boolean],
+    challenge: &UInt64,
+    layer: u32,
+) -> Result<num::AllocatedNum<Bls12>, SynthesisError> {
+    let preimage = label_preimage(cs.namespace(|| "preimage"), replica_id, challenge, layer)?;
+    PoseidonFunction::hash_md_circuit::<_>(&mut cs.namespace(|| "label"), &preimage)
+}
+
+/// Derives the label of an expander-layer node: as [`derive_first_layer_leaf`],
+/// additionally folding in the labels of its `degree_expander` parents.
+pub fn derive_expander_layer_leaf<CS: ConstraintSystem<Bls12>>(
+    mut cs: CS,
+    replica_id: &[Boolean],
+    challenge: &UInt64,
+    layer: u32,
+    config: &Config,
+    parents: &[num::AllocatedNum<Bls12>],
+) -> Result<num::AllocatedNum<Bls12>, SynthesisError> {
+    assert_eq!(
+        parents.len(),
+        config.degree_expander,
+        "wrong number of expander parents"
+    );
+
+    let mut preimage = label_preimage(cs.namespace(|| "preimage"), replica_id, challenge, layer)?;
+    preimage.extend(parents.iter().cloned());
+
+    PoseidonFunction::hash_md_circuit::<_>(&mut cs.namespace(|| "label"), &preimage)
+}
+
+/// Derives the label of a butterfly-layer node: as [`derive_first_layer_leaf`],
+/// additionally folding in the labels of its `degree_butterfly` butterfly
+/// parents. Unlike expander parents, butterfly parents live in the node's
+/// own layer tree rather than the previous one.
+pub fn derive_butterfly_layer_leaf<CS: ConstraintSystem<Bls12>>(
+    mut cs: CS,
+    replica_id: &[Boolean],
+    challenge: &UInt64,
+    layer: u32,
+    config: &Config,
+    parents: &[num::AllocatedNum<Bls12>],
+) -> Result<num::AllocatedNum<Bls12>, SynthesisError> {
+    assert_eq!(
+        parents.len(),
+        config.degree_butterfly,
+        "wrong number of butterfly parents"
+    );
+
+    let mut preimage = label_preimage(cs.namespace(|| "preimage"), replica_id, challenge, layer)?;
+    preimage.extend(parents.iter().cloned());
+
+    PoseidonFunction::hash_md_circuit::<_>(&mut cs.namespace(|| "label"), &preimage)
+}
+
+/// Derives the label of the last layer's node: a butterfly-style label as in
+/// [`derive_butterfly_layer_leaf`], used as the key to encode the data node
+/// already proven present in tree D, yielding the stored replica leaf.
+pub fn derive_last_layer_leaf<CS: ConstraintSystem<Bls12>>(
+    mut cs: CS,
+    replica_id: &[Boolean],
+    challenge: &UInt64,
+    layer: u32,
+    config: &Config,
+    parents: &[num::AllocatedNum<Bls12>],
+    data_leaf: &num::AllocatedNum<Bls12>,
+) -> Result<num::AllocatedNum<Bls12>, SynthesisError> {
+    let key = derive_butterfly_layer_leaf(
+        cs.namespace(|| "key"),
+        replica_id,
+        challenge,
+        layer,
+        config,
+        parents,
+    )?;
+
+    encode::encode(cs.namespace(|| "encode"), &key, data_leaf)
+}
@@ -0,0 +1,46 @@
+mod circuit;
+mod graph;
+mod hash;
+
+pub use circuit::NseCircuit;
+
+use generic_array::typenum::{U0, U2};
+use paired::bls12_381::Fr;
+use storage_proofs_core::{gadgets::por::AuthPath, hasher::Hasher, merkle::MerkleTreeTrait};
+
+/// A Merkle inclusion path through one of the per-layer trees (`comm_layers`),
+/// using that tree's own arity.
+pub type MerklePath<Tree> = AuthPath<
+    <Tree as MerkleTreeTrait>::Hasher,
+    <Tree as MerkleTreeTrait>::Arity,
+    <Tree as MerkleTreeTrait>::SubTreeArity,
+    <Tree as MerkleTreeTrait>::TopTreeArity,
+>;
+
+/// A Merkle inclusion path through the (binary) original-data tree, `comm_d`.
+pub type DataMerklePath<G> = AuthPath<G, U2, U0, U0>;
+
+/// Private inputs proving that a single challenged node is present in both
+/// the original data tree and the relevant layer tree, together with the
+/// parents its label was derived from (when that layer's label depends on
+/// parents).
+pub struct NodeProof<Tree: MerkleTreeTrait, G: Hasher> {
+    pub(crate) challenge: Option<u64>,
+    pub(crate) data_path: DataMerklePath<G>,
+    pub(crate) data_leaf: Option<Fr>,
+    pub(crate) layer_path: MerklePath<Tree>,
+    /// `(parent index, parent label)`, in the deterministic order the
+    /// layer's graph assigns parents to this node.
+    pub(crate) parents: Vec<(u64, Option<Fr>)>,
+    /// Inclusion path for each entry of `parents`, rooted at whichever tree
+    /// that parent actually lives in (see `LayerProof::synthesize`).
+    pub(crate) parents_paths: Vec<MerklePath<Tree>>,
+}
+
+/// Private inputs for all the layers touched by a single challenge.
+pub struct LayerProof<Tree: MerkleTreeTrait, G: Hasher> {
+    pub(crate) first_layer_proof: NodeProof<Tree, G>,
+    pub(crate) expander_layer_proofs: Vec<NodeProof<Tree, G>>,
+    pub(crate) butterfly_layer_proofs: Vec<NodeProof<Tree, G>>,
+    pub(crate) last_layer_proof: NodeProof<Tree, G>,
+}
@@ -0,0 +1,35 @@
+mod circuit;
+
+pub use circuit::NsePostCircuit;
+
+use paired::bls12_381::Fr;
+use storage_proofs_core::{
+    gadgets::por::AuthPath, hasher::Hasher, merkle::MerkleTreeTrait, sector::SectorId,
+};
+
+/// A Merkle inclusion path through one of a sector's layer trees.
+pub type MerklePath<Tree> = AuthPath<
+    <Tree as MerkleTreeTrait>::Hasher,
+    <Tree as MerkleTreeTrait>::Arity,
+    <Tree as MerkleTreeTrait>::SubTreeArity,
+    <Tree as MerkleTreeTrait>::TopTreeArity,
+>;
+
+/// Private witnesses proving one challenged leaf of a sector's last NSE
+/// layer.
+pub struct LeafProof<Tree: MerkleTreeTrait> {
+    pub(crate) challenge: Option<u64>,
+    pub(crate) leaf: Option<Fr>,
+    pub(crate) path: MerklePath<Tree>,
+}
+
+/// Private witnesses for one challenged sector: its id (so the verifier can
+/// recompute [`crate::nse::post::derive_challenges`] and check it against
+/// `leaf_proofs`), the commitments binding `comm_r = H(comm_layers)`, and the
+/// opened leaves of its last layer.
+pub struct SectorProof<Tree: MerkleTreeTrait> {
+    pub(crate) sector_id: Option<SectorId>,
+    pub(crate) comm_r: Option<<Tree::Hasher as Hasher>::Domain>,
+    pub(crate) comm_layers: Vec<Option<<Tree::Hasher as Hasher>::Domain>>,
+    pub(crate) leaf_proofs: Vec<LeafProof<Tree>>,
+}
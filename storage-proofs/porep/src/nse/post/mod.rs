@@ -0,0 +1,13 @@
+//! Fallback/window PoSt over NSE replicas.
+//!
+//! Proves that a set of already-replicated sectors are still stored, by
+//! opening `challenge_count` challenged leaves of each sector's last NSE
+//! layer against that sector's `comm_r`, without revealing the data itself.
+//! Challenges are derived deterministically (Fiat-Shamir) from a public
+//! `randomness` and each sector's id, so the prover cannot choose which
+//! nodes it has to answer for.
+
+pub mod circuit;
+pub mod vanilla;
+
+pub use vanilla::{derive_challenges, verify_challenges, ProvenSector, PublicInputs, SetupParams};
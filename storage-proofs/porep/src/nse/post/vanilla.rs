@@ -0,0 +1,181 @@
+use sha2::{Digest, Sha256};
+use storage_proofs_core::sector::SectorId;
+
+/// Parameters for a fallback/window PoSt partition over NSE replicas.
+#[derive(Debug, Clone)]
+pub struct SetupParams {
+    pub sector_size: u64,
+    pub challenge_count: usize,
+    pub sector_count: usize,
+}
+
+/// Deterministically derives `challenge_count` challenged node indices for
+/// `sector_id`, Fiat-Shamir style, from the partition's public `randomness`.
+/// Both prover and verifier compute this independently, so the prover cannot
+/// choose which nodes of the sector it has to answer for.
+pub fn derive_challenges(
+    randomness: [u8; 32],
+    sector_id: SectorId,
+    challenge_count: usize,
+    num_nodes_sector: u64,
+) -> Vec<u64> {
+    (0..challenge_count)
+        .map(|i| {
+            let mut hasher = Sha256::new();
+            hasher.update(&randomness);
+            hasher.update(&u64::from(sector_id).to_le_bytes());
+            hasher.update(&(i as u64).to_le_bytes());
+            let digest = hasher.finalize();
+
+            let mut le_bytes = [0u8; 8];
+            le_bytes.copy_from_slice(&digest[..8]);
+            u64::from_le_bytes(le_bytes) % num_nodes_sector
+        })
+        .collect()
+}
+
+/// Public inputs to a fallback/window PoSt partition: the Fiat-Shamir
+/// randomness every challenged sector's indices were derived from.
+#[derive(Debug, Clone)]
+pub struct PublicInputs {
+    pub randomness: [u8; 32],
+}
+
+/// One challenged sector's id together with the node indices its
+/// `SectorProof` claims to open -- enough to recompute and check
+/// [`derive_challenges`] against what was actually proven.
+#[derive(Debug, Clone)]
+pub struct ProvenSector {
+    pub sector_id: SectorId,
+    pub challenges: Vec<u64>,
+}
+
+/// Verifies that a partition proof answers for the right number of sectors,
+/// the right number of challenges per sector, and that every challenge is
+/// exactly the one [`derive_challenges`] assigns that sector -- i.e. that the
+/// prover didn't get to choose which nodes it opened.
+pub fn verify_challenges(
+    public_inputs: &PublicInputs,
+    setup_params: &SetupParams,
+    num_nodes_sector: u64,
+    proven: &[ProvenSector],
+) -> bool {
+    if proven.len() != setup_params.sector_count {
+        return false;
+    }
+
+    proven.iter().all(|sector| {
+        sector.challenges
+            == derive_challenges(
+                public_inputs.randomness,
+                sector.sector_id,
+                setup_params.challenge_count,
+                num_nodes_sector,
+            )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_challenges_accepts_correctly_derived_challenges() {
+        let randomness = [7u8; 32];
+        let setup_params = SetupParams {
+            sector_size: 2048,
+            challenge_count: 3,
+            sector_count: 2,
+        };
+        let num_nodes_sector = 64;
+
+        let proven = vec![
+            ProvenSector {
+                sector_id: SectorId::from(1),
+                challenges: derive_challenges(
+                    randomness,
+                    SectorId::from(1),
+                    setup_params.challenge_count,
+                    num_nodes_sector,
+                ),
+            },
+            ProvenSector {
+                sector_id: SectorId::from(2),
+                challenges: derive_challenges(
+                    randomness,
+                    SectorId::from(2),
+                    setup_params.challenge_count,
+                    num_nodes_sector,
+                ),
+            },
+        ];
+
+        let public_inputs = PublicInputs { randomness };
+        assert!(verify_challenges(
+            &public_inputs,
+            &setup_params,
+            num_nodes_sector,
+            &proven,
+        ));
+    }
+
+    #[test]
+    fn verify_challenges_rejects_prover_chosen_challenges() {
+        let randomness = [7u8; 32];
+        let setup_params = SetupParams {
+            sector_size: 2048,
+            challenge_count: 3,
+            sector_count: 1,
+        };
+        let num_nodes_sector = 64;
+
+        let mut proven = vec![ProvenSector {
+            sector_id: SectorId::from(1),
+            challenges: derive_challenges(
+                randomness,
+                SectorId::from(1),
+                setup_params.challenge_count,
+                num_nodes_sector,
+            ),
+        }];
+        // The prover substitutes a node it would rather answer for.
+        proven[0].challenges[0] = (proven[0].challenges[0] + 1) % num_nodes_sector;
+
+        let public_inputs = PublicInputs { randomness };
+        assert!(!verify_challenges(
+            &public_inputs,
+            &setup_params,
+            num_nodes_sector,
+            &proven,
+        ));
+    }
+
+    #[test]
+    fn verify_challenges_rejects_wrong_sector_count() {
+        let randomness = [7u8; 32];
+        let setup_params = SetupParams {
+            sector_size: 2048,
+            challenge_count: 3,
+            sector_count: 2,
+        };
+        let num_nodes_sector = 64;
+
+        let proven = vec![ProvenSector {
+            sector_id: SectorId::from(1),
+            challenges: derive_challenges(
+                randomness,
+                SectorId::from(1),
+                setup_params.challenge_count,
+                num_nodes_sector,
+            ),
+        }];
+
+        let public_inputs = PublicInputs { randomness };
+        assert!(!verify_challenges(
+            &public_inputs,
+            &setup_params,
+            num_nodes_sector,
+            &proven,
+        ));
+    }
+}
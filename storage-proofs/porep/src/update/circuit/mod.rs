@@ -0,0 +1,37 @@
+mod circuit;
+
+pub use circuit::EmptySectorUpdateCircuit;
+
+use generic_array::typenum::{U0, U2};
+use paired::bls12_381::Fr;
+use storage_proofs_core::{gadgets::por::AuthPath, hasher::Hasher, merkle::MerkleTreeTrait};
+
+/// A Merkle inclusion path through one of the replica trees (`comm_r_old`,
+/// `comm_r_new`), using that tree's own arity.
+pub type MerklePath<Tree> = AuthPath<
+    <Tree as MerkleTreeTrait>::Hasher,
+    <Tree as MerkleTreeTrait>::Arity,
+    <Tree as MerkleTreeTrait>::SubTreeArity,
+    <Tree as MerkleTreeTrait>::TopTreeArity,
+>;
+
+/// A Merkle inclusion path through the (binary) new-data tree, `comm_d_new`.
+pub type DataMerklePath<G> = AuthPath<G, U2, U0, U0>;
+
+/// Private witnesses for a single challenged node: the leaf already stored
+/// in the old replica, the new data leaf being encoded in, and the path
+/// proving the recomputed replica leaf lives in the new replica.
+///
+/// `rho`, the encoding key's randomness, is *not* one of these -- it is
+/// derived in-circuit from the partition's public `rho_seed` and this
+/// node's own `challenge` (see `ChallengeProof::synthesize`), so that a
+/// prover can't satisfy the encoding relation by picking `rho` freely for
+/// whichever leaves it happens to have opened.
+pub struct ChallengeProof<Tree: MerkleTreeTrait, G: Hasher> {
+    pub(crate) challenge: Option<u64>,
+    pub(crate) old_replica_path: MerklePath<Tree>,
+    pub(crate) old_replica_leaf: Option<Fr>,
+    pub(crate) new_data_path: DataMerklePath<G>,
+    pub(crate) new_data_leaf: Option<Fr>,
+    pub(crate) new_replica_path: MerklePath<Tree>,
+}
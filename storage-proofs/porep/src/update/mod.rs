@@ -0,0 +1,19 @@
+//! Empty-sector-update ("snap") proof scheme.
+//!
+//! Lets a sector holder prove that a new replica `comm_r_new` was produced
+//! from the previously-sealed replica `comm_r_old` by encoding new data
+//! `comm_d_new` into it, without re-running NSE replication. This is orders
+//! of magnitude cheaper than a full PoRep and shares its Merkle-inclusion and
+//! challenge-packing gadgets with [`crate::nse::circuit`].
+
+pub mod circuit;
+pub mod vanilla;
+
+pub use vanilla::{derive_challenges, verify_challenge_count, verify_challenges, PublicInputs};
+
+/// Parameters shared by the vanilla and circuit halves of the scheme.
+#[derive(Debug, Clone)]
+pub struct PublicParams {
+    pub sector_size: u64,
+    pub challenge_count: usize,
+}
@@ -0,0 +1,133 @@
+//! Vanilla (off-circuit) half of the empty-sector-update scheme: the public
+//! input shape the circuit's three commitments come from, and the
+//! deterministic challenge derivation that keeps the prover from choosing
+//! which nodes it re-encodes.
+
+use sha2::{Digest, Sha256};
+
+use super::PublicParams;
+
+/// Public inputs to an empty-sector-update proof: the three commitments the
+/// circuit's `comm_r_old`/`comm_r_new`/`comm_d_new` are bound to.
+#[derive(Debug, Clone)]
+pub struct PublicInputs<ReplicaDomain, DataDomain> {
+    pub comm_r_old: ReplicaDomain,
+    pub comm_r_new: ReplicaDomain,
+    pub comm_d_new: DataDomain,
+}
+
+/// Deterministically derives `challenge_count` challenged node indices from
+/// `comm_r_new`, Fiat-Shamir style, so prover and verifier agree on which
+/// nodes get re-encoded and opened without the prover choosing them.
+pub fn derive_challenges<D: AsRef<[u8]>>(
+    comm_r_new: D,
+    challenge_count: usize,
+    num_nodes: u64,
+) -> Vec<u64> {
+    (0..challenge_count)
+        .map(|i| {
+            let mut hasher = Sha256::new();
+            hasher.update(comm_r_new.as_ref());
+            hasher.update(&(i as u64).to_le_bytes());
+            let digest = hasher.finalize();
+
+            let mut le_bytes = [0u8; 8];
+            le_bytes.copy_from_slice(&digest[..8]);
+            u64::from_le_bytes(le_bytes) % num_nodes
+        })
+        .collect()
+}
+
+/// Checks that a proof answers for exactly as many challenges as
+/// `public_params` promises.
+pub fn verify_challenge_count(public_params: &PublicParams, challenge_proofs_len: usize) -> bool {
+    challenge_proofs_len == public_params.challenge_count
+}
+
+/// Verifies a proof's challenge list outright: that it answers for the right
+/// number of challenges, and that those challenges are exactly the ones
+/// [`derive_challenges`] assigns `comm_r_new` -- the two checks a verifier
+/// needs so it never has to trust the prover's choice of which nodes got
+/// re-encoded. Mirrors [`crate::nse::post::verify_challenges`]'s identity
+/// check, minus the per-sector dimension this single-commitment scheme
+/// doesn't have.
+pub fn verify_challenges<D: AsRef<[u8]>>(
+    public_params: &PublicParams,
+    comm_r_new: D,
+    num_nodes: u64,
+    proven_challenges: &[u64],
+) -> bool {
+    verify_challenge_count(public_params, proven_challenges.len())
+        && proven_challenges
+            == derive_challenges(comm_r_new, public_params.challenge_count, num_nodes).as_slice()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derive_challenges_is_deterministic_and_in_range() {
+        let comm_r_new = [9u8; 32];
+        let num_nodes = 64;
+
+        let a = derive_challenges(comm_r_new, 5, num_nodes);
+        let b = derive_challenges(comm_r_new, 5, num_nodes);
+        assert_eq!(a, b, "challenge derivation must be deterministic");
+        assert!(a.iter().all(|&c| c < num_nodes));
+
+        let different = derive_challenges([1u8; 32], 5, num_nodes);
+        assert_ne!(a, different, "different comm_r_new must yield different challenges");
+    }
+
+    #[test]
+    fn verify_challenge_count_checks_length() {
+        let public_params = PublicParams {
+            sector_size: 2048,
+            challenge_count: 4,
+        };
+
+        assert!(verify_challenge_count(&public_params, 4));
+        assert!(!verify_challenge_count(&public_params, 3));
+    }
+
+    #[test]
+    fn verify_challenges_accepts_correctly_derived_challenges() {
+        let comm_r_new = [9u8; 32];
+        let num_nodes = 64;
+        let public_params = PublicParams {
+            sector_size: 2048,
+            challenge_count: 5,
+        };
+
+        let proven = derive_challenges(comm_r_new, public_params.challenge_count, num_nodes);
+
+        assert!(verify_challenges(
+            &public_params,
+            comm_r_new,
+            num_nodes,
+            &proven,
+        ));
+    }
+
+    #[test]
+    fn verify_challenges_rejects_prover_chosen_challenges() {
+        let comm_r_new = [9u8; 32];
+        let num_nodes = 64;
+        let public_params = PublicParams {
+            sector_size: 2048,
+            challenge_count: 5,
+        };
+
+        let mut proven = derive_challenges(comm_r_new, public_params.challenge_count, num_nodes);
+        // The prover substitutes a node it would rather re-encode.
+        proven[0] = (proven[0] + 1) % num_nodes;
+
+        assert!(!verify_challenges(
+            &public_params,
+            comm_r_new,
+            num_nodes,
+            &proven,
+        ));
+    }
+}